@@ -1,20 +1,122 @@
+use std::any::Any;
 use std::path::Path;
 use std::collections::HashMap;
 
 use serde::Serialize;
+use serde_json::Value;
 
 use crate::templates::TemplateInfo;
 
 #[cfg(feature = "tera_templates")] use crate::templates::tera::Tera;
 #[cfg(feature = "handlebars_templates")] use crate::templates::handlebars::Handlebars;
 
-pub(crate) trait Engine: Send + Sync + Sized + 'static {
+/// The source of a template's contents, passed to [`Engine::init`].
+///
+/// A template is usually discovered on disk and provided as a `File`, but it
+/// can also be registered in-memory as a `Raw` string. The latter allows
+/// templates to be baked into the binary with `include_str!`, so that a
+/// deployment doesn't need to ship loose template files alongside it.
+pub enum TemplateSource<'a> {
+    /// A template discovered at the given path on the filesystem.
+    File(&'a Path),
+    /// A template registered in-memory with the given raw contents.
+    Raw(&'a str),
+}
+
+/// A named, in-memory template supplied directly to [`Engines::init`],
+/// independent of whatever `TemplateInfo`-based on-disk discovery the
+/// context loader performs.
+///
+/// This is the producer side of [`TemplateSource::Raw`]: a context loader
+/// that wants to bake templates into the binary with `include_str!`
+/// collects them as `RawTemplate`s and passes them to `Engines::init`
+/// alongside the discovered `TemplateInfo` map, so they reach the built-in
+/// engines' [`Engine::init`] the same way a file found on disk would. An
+/// engine added later with [`Engines::register`] is already constructed and
+/// does not receive the raw sources; for such entries only the name and
+/// extension are recorded.
+pub struct RawTemplate {
+    /// The template's name, as it will be addressed by `render`.
+    pub name: String,
+    /// The engine extension, e.g. `tera`, that should render this template.
+    pub engine_ext: String,
+    /// The template's raw contents.
+    pub source: String,
+}
+
+/// Trait implemented by types that can be used as a Rocket templating
+/// engine.
+///
+/// `Tera` and `Handlebars` implement this trait out of the box, gated behind
+/// the `tera_templates` and `handlebars_templates` features, respectively.
+/// Implement it yourself to plug in a different engine (Liquid, Askama,
+/// minijinja, ...) and register it with [`Engines::register`], typically
+/// from a [`Template::custom`] fairing callback:
+///
+/// ```rust
+/// # use rocket_contrib::templates::{Engine, Engines, TemplateSource};
+/// struct MyEngine;
+///
+/// impl Engine for MyEngine {
+///     const EXT: &'static str = "my";
+///
+///     fn init<'a>(_templates: impl Iterator<Item = (&'a str, TemplateSource<'a>)>) -> Option<Self> {
+///         Some(MyEngine)
+///     }
+///
+///     fn render<C: serde::Serialize>(&self, _name: &str, _context: C) -> Option<String> {
+///         None
+///     }
+/// }
+///
+/// # fn register(engines: &mut Engines) {
+/// engines.register(MyEngine);
+/// # }
+/// ```
+///
+/// [`Template::custom`]: crate::templates::Template::custom
+pub trait Engine: Send + Sync + 'static {
+    /// The extension to match against a template's last extension, i.e.
+    /// `tera` for `index.html.tera`.
     const EXT: &'static str;
 
-    fn init<'a>(templates: impl Iterator<Item = (&'a str, &'a Path)>) -> Option<Self>;
+    /// Initializes the engine from the `name`, `source` pairs of all
+    /// discovered templates whose extension matches `Self::EXT`.
+    fn init<'a>(templates: impl Iterator<Item = (&'a str, TemplateSource<'a>)>) -> Option<Self>
+        where Self: Sized;
+
+    /// Renders the template named `name` with context `context`.
     fn render<C: Serialize>(&self, name: &str, context: C) -> Option<String>;
 }
 
+/// Object-safe shadow of [`Engine`], implemented for every `E: Engine`, that
+/// lets `Engines` store a heterogeneous list of engines behind `Box<dyn
+/// ErasedEngine>` and dispatch to them without knowing their concrete type.
+trait ErasedEngine: Any + Send + Sync {
+    fn ext(&self) -> &'static str;
+    fn render_erased(&self, name: &str, context: &Value) -> Option<String>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<E: Engine> ErasedEngine for E {
+    fn ext(&self) -> &'static str {
+        E::EXT
+    }
+
+    fn render_erased(&self, name: &str, context: &Value) -> Option<String> {
+        Engine::render(self, name, context)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
 /// A structure exposing access to templating engines.
 ///
 /// Calling methods on the exposed template engine types may require importing
@@ -41,7 +143,7 @@ pub(crate) trait Engine: Send + Sync + Sized + 'static {
 ///     rocket::build()
 ///         // ...
 ///         .attach(Template::custom(|engines: &mut Engines| {
-///             engines.tera.register_filter("my_filter", my_filter);
+///             engines.get_mut::<tera::Tera>().unwrap().register_filter("my_filter", my_filter);
 ///         }))
 ///         // ...
 ///         # ;
@@ -49,51 +151,141 @@ pub(crate) trait Engine: Send + Sync + Sized + 'static {
 /// # }
 /// ```
 ///
+/// Engines other than the built-in `Tera` and `Handlebars` engines, including
+/// ones supplied by downstream crates, can be added to the mix with
+/// [`Engines::register`]. Values shared by every template, such as a site
+/// name or version, can be registered once with [`Engines::set_global`]
+/// instead of being threaded through each handler's context. Related
+/// templates, such as an email's subject and body, can be rendered together
+/// against the same context with [`Engines::render_group`].
+///
 /// [`tera::Value`]: crate::templates::tera::Value
 /// [`tera::Result`]: crate::templates::tera::Result
 pub struct Engines {
-    /// A `Tera` templating engine. This field is only available when the
-    /// `tera_templates` feature is enabled. When calling methods on the `Tera`
-    /// instance, ensure you use types imported from
-    /// `rocket_contrib::templates::tera` to avoid version mismatches.
-    #[cfg(feature = "tera_templates")]
-    pub tera: Tera,
-    /// The Handlebars templating engine. This field is only available when the
-    /// `handlebars_templates` feature is enabled. When calling methods on the
-    /// `Tera` instance, ensure you use types imported from
-    /// `rocket_contrib::templates::handlebars` to avoid version mismatches.
-    #[cfg(feature = "handlebars_templates")]
-    pub handlebars: Handlebars<'static>,
+    engines: Vec<Box<dyn ErasedEngine>>,
+    loaded: Vec<(String, String)>,
+    globals: serde_json::Map<String, Value>,
+}
+
+/// Deep-merges `overlay` into `base`, recursing into nested objects and
+/// otherwise letting `overlay` win on key conflicts.
+fn deep_merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => deep_merge(existing, value),
+                    None => { base.insert(key, value); }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
 }
 
 impl Engines {
+    /// The engine extensions recognized by the built-in engines. Used by the
+    /// template discovery/context loader to decide which files on disk look
+    /// like templates; on-disk discovery is still limited to this set, so a
+    /// custom engine registered with [`Engines::register`] can only render
+    /// templates supplied as a [`RawTemplate`] (whose `engine_ext` isn't
+    /// checked against this set) rather than ones found by walking a
+    /// directory. `Engines::loaded`, by contrast, tracks every template
+    /// passed into [`Engines::init`] regardless of extension -- both on-disk
+    /// and raw -- so that `templates()` and `render_group` can see members
+    /// served by any registered engine, not just the built-in ones.
     pub(crate) const ENABLED_EXTENSIONS: &'static [&'static str] = &[
         #[cfg(feature = "tera_templates")] Tera::EXT,
         #[cfg(feature = "handlebars_templates")] Handlebars::EXT,
     ];
 
-    pub(crate) fn init(templates: &HashMap<String, TemplateInfo>) -> Option<Engines> {
-        fn inner<E: Engine>(templates: &HashMap<String, TemplateInfo>) -> Option<E> {
-            let named_templates = templates.iter()
+    pub(crate) fn init(
+        templates: &HashMap<String, TemplateInfo>,
+        raw_templates: &[RawTemplate],
+    ) -> Option<Engines> {
+        fn inner<'a, E: Engine>(
+            templates: &'a HashMap<String, TemplateInfo>,
+            raw_templates: &'a [RawTemplate],
+        ) -> Option<E> {
+            let from_disk = templates.iter()
                 .filter(|&(_, i)| i.engine_ext == E::EXT)
-                .filter_map(|(k, i)| Some((k.as_str(), i.path.as_ref()?)))
-                .map(|(k, p)| (k, p.as_path()));
+                .filter_map(|(k, i)| Some((k.as_str(), TemplateSource::File(i.path.as_deref()?))));
+
+            let from_raw = raw_templates.iter()
+                .filter(|r| r.engine_ext == E::EXT)
+                .map(|r| (r.name.as_str(), TemplateSource::Raw(r.source.as_str())));
 
-            E::init(named_templates)
+            E::init(from_disk.chain(from_raw))
         }
 
-        Some(Engines {
-            #[cfg(feature = "tera_templates")]
-            tera: match inner::<Tera>(templates) {
-                Some(tera) => tera,
-                None => return None
-            },
-            #[cfg(feature = "handlebars_templates")]
-            handlebars: match inner::<Handlebars<'static>>(templates) {
-                Some(hb) => hb,
-                None => return None
-            },
-        })
+        let mut engines = Engines { engines: vec![], loaded: vec![], globals: serde_json::Map::new() };
+
+        #[cfg(feature = "tera_templates")] {
+            engines.engines.push(Box::new(inner::<Tera>(templates, raw_templates)?));
+        }
+
+        #[cfg(feature = "handlebars_templates")] {
+            engines.engines.push(Box::new(inner::<Handlebars<'static>>(templates, raw_templates)?));
+        }
+
+        // Track every discovered template regardless of extension (not just
+        // Self::ENABLED_EXTENSIONS), so that templates() and render_group can
+        // see members served by a custom engine via its own RawTemplate
+        // entries, which carry their own engine_ext and so aren't limited to
+        // the built-in extensions on-disk discovery looks for.
+        engines.loaded = templates.iter()
+            .map(|(k, i)| (k.clone(), i.engine_ext.clone()))
+            .chain(raw_templates.iter().map(|r| (r.name.clone(), r.engine_ext.clone())))
+            .collect();
+
+        Some(engines)
+    }
+
+    /// Registers a custom templating engine, making it available for
+    /// rendering templates whose engine extension matches `E::EXT`.
+    ///
+    /// Intended to be called from a [`Template::custom`] fairing callback to
+    /// plug in an engine other than the built-in `Tera` and `Handlebars`
+    /// engines.
+    ///
+    /// [`Template::custom`]: crate::templates::Template::custom
+    pub fn register<E: Engine>(&mut self, engine: E) {
+        self.engines.push(Box::new(engine));
+    }
+
+    /// Returns a reference to the registered engine of type `E`, if any is
+    /// registered.
+    pub fn get<E: Engine>(&self) -> Option<&E> {
+        self.engines.iter()
+            .find_map(|e| e.as_any().downcast_ref::<E>())
+    }
+
+    /// Returns a mutable reference to the registered engine of type `E`, if
+    /// any is registered.
+    pub fn get_mut<E: Engine>(&mut self) -> Option<&mut E> {
+        self.engines.iter_mut()
+            .find_map(|e| e.as_any_mut().downcast_mut::<E>())
+    }
+
+    /// Sets `key` to `value` in the global context that is merged into every
+    /// template's context at render time. Intended to be called from a
+    /// [`Template::custom`] fairing callback to make site-wide values (site
+    /// name, version, asset paths, ...) available to every template without
+    /// threading them through each handler's context.
+    ///
+    /// A key set per-call to [`render`](Engines::render) takes precedence
+    /// over a global with the same key.
+    ///
+    /// Returns `false`, without recording `key`, if `value` fails to
+    /// serialize; callers that need to know about such a failure should
+    /// check the return value rather than assume the global was set.
+    ///
+    /// [`Template::custom`]: crate::templates::Template::custom
+    pub fn set_global(&mut self, key: impl Into<String>, value: impl Serialize) -> bool {
+        match serde_json::to_value(value) {
+            Ok(value) => { self.globals.insert(key.into(), value); true }
+            Err(_) => false,
+        }
     }
 
     pub(crate) fn render<C: Serialize>(
@@ -102,37 +294,142 @@ impl Engines {
         info: &TemplateInfo,
         context: C
     ) -> Option<String> {
-        #[cfg(feature = "tera_templates")] {
-            if info.engine_ext == Tera::EXT {
-                return Engine::render(&self.tera, name, context);
-            }
-        }
-
-        #[cfg(feature = "handlebars_templates")] {
-            if info.engine_ext == Handlebars::EXT {
-                return Engine::render(&self.handlebars, name, context);
-            }
-        }
+        let mut merged = Value::Object(self.globals.clone());
+        deep_merge(&mut merged, serde_json::to_value(context).ok()?);
 
-        None
+        self.engines.iter()
+            .find(|e| e.ext() == info.engine_ext)
+            .and_then(|e| e.render_erased(name, &merged))
     }
 
     /// Returns iterator over template (name, engine_extension).
-    pub(crate) fn templates(&self) -> impl Iterator<Item = (&str, &'static str)> {
-        #[cfg(all(feature = "tera_templates", feature = "handlebars_templates"))] {
-            self.tera.get_template_names()
-                .map(|name| (name, Tera::EXT))
-                .chain(self.handlebars.get_templates().keys()
-                    .map(|name| (name.as_str(), Handlebars::EXT)))
-        }
+    pub(crate) fn templates(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.loaded.iter().map(|(name, ext)| (name.as_str(), ext.as_str()))
+    }
+
+    /// Renders every template in `group` against `context`, returning the
+    /// rendered output of each member keyed by its role.
+    ///
+    /// A template belongs to `group` if its name has the form
+    /// `{group}.{role}.{ext}`; for example, `mail/welcome.subject.txt` and
+    /// `mail/welcome.html.tera` both belong to the `mail/welcome` group, with
+    /// roles `subject` and `html` respectively. This lets a single logical
+    /// unit, such as an email's subject and body, be rendered together
+    /// against the same data rather than one piece at a time.
+    ///
+    /// Members whose engine extension has no registered engine are skipped.
+    /// Returns `None` if `group` has no rendered members or if rendering any
+    /// member fails.
+    pub fn render_group<C: Serialize>(&self, group: &str, context: C) -> Option<MultiPart> {
+        let mut merged = Value::Object(self.globals.clone());
+        deep_merge(&mut merged, serde_json::to_value(context).ok()?);
+
+        let mut parts = HashMap::new();
+        for (name, ext) in self.loaded.iter().map(|(n, e)| (n.as_str(), e.as_str())) {
+            let role = match group_role(name, group, ext) {
+                Some(role) => role,
+                None => continue,
+            };
+
+            let engine = match self.engines.iter().find(|e| e.ext() == ext) {
+                Some(engine) => engine,
+                None => continue,
+            };
 
-        #[cfg(all(feature = "tera_templates", not(feature = "handlebars_templates")))] {
-            self.tera.get_template_names().map(|name| (name, Tera::EXT))
+            let rendered = engine.render_erased(name, &merged)?;
+            parts.insert(role.to_string(), rendered);
         }
 
-        #[cfg(all(feature = "handlebars_templates", not(feature = "tera_templates")))] {
-            self.handlebars.get_templates().keys()
-                .map(|name| (name.as_str(), Handlebars::EXT))
+        if parts.is_empty() {
+            return None;
         }
+
+        Some(MultiPart { parts })
+    }
+}
+
+/// If `name` is a member of `group` (i.e. has the form `{group}.{role}` or
+/// `{group}.{role}.{ext'}`), returns its role. `name` bare-equal to
+/// `{group}.{ext}` (no role segment at all) means `group` itself names a
+/// plain, ungrouped template rather than a group, and returns `None`.
+fn group_role<'a>(name: &'a str, group: &str, ext: &str) -> Option<&'a str> {
+    let rest = name.strip_prefix(group)?.strip_prefix('.')?;
+    if rest == ext || rest.is_empty() {
+        return None;
+    }
+
+    Some(rest.split_once('.').map_or(rest, |(role, _)| role))
+}
+
+/// The rendered members of a template group, as returned by
+/// [`Engines::render_group`], keyed by role (e.g. `"subject"`, `"html"`,
+/// `"txt"`).
+pub struct MultiPart {
+    parts: HashMap<String, String>,
+}
+
+impl MultiPart {
+    /// Returns the rendered output for `role`, if it was a member of the
+    /// group.
+    pub fn get(&self, role: &str) -> Option<&str> {
+        self.parts.get(role).map(|s| s.as_str())
+    }
+
+    /// Returns an iterator over the `(role, rendered)` pairs in this group.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.parts.iter().map(|(role, text)| (role.as_str(), text.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{deep_merge, group_role};
+    use serde_json::json;
+
+    #[test]
+    fn deep_merge_overlay_wins_on_conflicting_scalars() {
+        let mut base = json!({ "a": 1, "b": 2 });
+        deep_merge(&mut base, json!({ "b": 3 }));
+        assert_eq!(base, json!({ "a": 1, "b": 3 }));
+    }
+
+    #[test]
+    fn deep_merge_recurses_into_nested_objects() {
+        let mut base = json!({ "site": { "name": "Rocket", "version": 1 } });
+        deep_merge(&mut base, json!({ "site": { "version": 2 }, "user": "Bob" }));
+        assert_eq!(base, json!({
+            "site": { "name": "Rocket", "version": 2 },
+            "user": "Bob",
+        }));
+    }
+
+    #[test]
+    fn deep_merge_overlay_replaces_non_object_with_object_and_vice_versa() {
+        let mut base = json!({ "a": { "nested": true } });
+        deep_merge(&mut base, json!({ "a": "scalar" }));
+        assert_eq!(base, json!({ "a": "scalar" }));
+
+        deep_merge(&mut base, json!({ "a": { "nested": true } }));
+        assert_eq!(base, json!({ "a": { "nested": true } }));
+    }
+
+    #[test]
+    fn group_role_splits_on_first_dot_after_the_group() {
+        assert_eq!(group_role("mail/welcome.subject.txt", "mail/welcome", "tera"), Some("subject"));
+    }
+
+    #[test]
+    fn group_role_falls_back_to_whole_remainder_for_single_segment_roles() {
+        assert_eq!(group_role("mail/welcome.html", "mail/welcome", "tera"), Some("html"));
+    }
+
+    #[test]
+    fn group_role_rejects_names_outside_the_group() {
+        assert_eq!(group_role("mail/other.html", "mail/welcome", "tera"), None);
+    }
+
+    #[test]
+    fn group_role_rejects_bare_group_name_matching_the_engine_extension() {
+        assert_eq!(group_role("mail/welcome.tera", "mail/welcome", "tera"), None);
     }
 }